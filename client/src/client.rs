@@ -1,19 +1,23 @@
-use tokio::net::TcpStream;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::lookup_host;
 use tokio_util::compat::{TokioAsyncReadCompatExt, FuturesAsyncReadCompatExt};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
 use yamux::{Config as YamuxConfig, Connection, Mode};
-use tokio_native_tls::TlsConnector;
-use log::{info, error};
+use log::{error, info};
 
 use crate::config::Config;
 use crate::error::Result;
-use crate::socks::SOCKClient;
+use crate::socks::SocksServer;
+use crate::tls::PinnedCertVerifier;
+use crate::transport::{BoxedStream, TlsTransport, Transport, TransportRegistry};
 
 const MAGIC_BYTES: [u8; 4] = [0x1b, 0xc3, 0xbd, 0x0f];
 
-trait AsyncStream: AsyncRead + AsyncWrite {}
-impl<T: AsyncRead + AsyncWrite> AsyncStream for T {}
-
 pub struct ReverseProxyClient {
     config: Config,
 }
@@ -25,47 +29,162 @@ impl ReverseProxyClient {
 
     pub async fn run(&self) -> Result<()> {
         info!("connecting to {}:{}", self.config.host, self.config.port);
-        
-        let stream = self.establish_connection().await?;
-        self.handle_connection(stream).await
+
+        let proxy_header = self.proxy_header().await?;
+
+        let transport = self.build_transport()?;
+        let stream: BoxedStream = transport.connect(&self.config.host, self.config.port).await?;
+        self.handle_connection(stream, proxy_header).await
+    }
+
+    // Assemble the transport chain selected by `Config::transport`. Built-in
+    // layers are registered by name; with no selection we default to TLS or
+    // plain TCP per the `tls` flag. Specs are written outermost-first (e.g.
+    // `obfs,tls`) so an obfuscation layer can wrap the TLS transport.
+    fn build_transport(&self) -> Result<Box<dyn Transport>> {
+        let spec = match &self.config.transport {
+            Some(spec) => spec.clone(),
+            None if self.config.tls => "tls".to_string(),
+            None => "tcp".to_string(),
+        };
+
+        let mut registry = TransportRegistry::new();
+
+        // The built-in TLS layer; the certificate configuration is rebuilt
+        // lazily so plain-TCP chains never touch the trust store.
+        let ca_bundle = self.config.ca_bundle.clone();
+        let pin = self.config.pin.clone();
+        registry.register("tls", move |inner| {
+            info!("using TLS transport");
+            let config = Arc::new(build_tls_config(&ca_bundle, &pin)?);
+            Ok(Box::new(TlsTransport::new(inner, config)) as Box<dyn Transport>)
+        });
+
+        registry.build(&spec)
     }
 
-    async fn establish_connection(&self) -> Result<Box<dyn AsyncStream + Send + Unpin + 'static>> {
-        let stream = TcpStream::connect(format!("{}:{}", self.config.host, self.config.port)).await?;
-
-        if self.config.tls {
-            info!("using TLS connection");
-            let mut builder = native_tls::TlsConnector::builder();
-            builder.danger_accept_invalid_certs(true);
-            let tls = TlsConnector::from(builder.build()?);
-            Ok(Box::new(tls.connect(&self.config.host, stream).await?))
-        } else {
-            Ok(Box::new(stream))
+    // Build an optional PROXY protocol v2 header describing the underlying TCP
+    // connection. The destination is the resolved agent-server address; the
+    // source is the local address the kernel selects for reaching it, so the
+    // listening server recovers the agent's real address rather than the
+    // multiplexed yamux socket.
+    //
+    // The header is built before the transport connects, so the real TCP
+    // source port does not yet exist and cannot be recovered through the boxed
+    // stream afterwards. We therefore report the source IP with port 0
+    // (unknown); downstream consumers should key on the source IP only.
+    async fn proxy_header(&self) -> Result<Option<Vec<u8>>> {
+        if !self.config.proxy_protocol {
+            return Ok(None);
         }
+
+        let dst = lookup_host(format!("{}:{}", self.config.host, self.config.port))
+            .await?
+            .next()
+            .ok_or_else(|| format!("could not resolve {}", self.config.host))?;
+        let src = SocketAddr::new(local_ip_for(dst)?, 0);
+        Ok(Some(crate::proxy::header_v2(src, dst)))
     }
 
-    async fn handle_connection<T: AsyncRead + AsyncWrite + Send + Unpin + 'static>(&self, mut stream: T) -> Result<()> {
+
+    async fn handle_connection<T: AsyncRead + AsyncWrite + Send + Unpin + 'static>(
+        &self,
+        mut stream: T,
+        proxy_header: Option<Vec<u8>>,
+    ) -> Result<()> {
         stream.write_all(&MAGIC_BYTES).await?;
 
+        if let Some(header) = proxy_header {
+            stream.write_all(&header).await?;
+        }
+
         let mut conn = Connection::new(stream.compat(), YamuxConfig::default(), Mode::Server);
 
+        // Credentials from `Config` enable RFC 1929 auth on every accepted
+        // SOCKS session; without both we fall back to no-auth.
+        let auth = match (&self.config.username, &self.config.password) {
+            (Some(user), Some(pass)) => Some((user.clone(), pass.clone())),
+            _ => None,
+        };
+
+        // When Tor mode is enabled, outbound connections are dialled through
+        // the configured SOCKS5 listener (default `127.0.0.1:9050`).
+        let tor = self.config.tor;
+        let tor_addr = self.config.tor_addr.clone();
+
         loop {
             let stream = match std::future::poll_fn(|cx| conn.poll_next_inbound(cx)).await {
                 Some(Ok(stream)) => stream,
                 Some(Err(e)) => {
-                    error!("Connection error: {:?}", e);
+                    error!("connection error: {:?}", e);
                     continue;
                 }
                 None => return Err("connection closed".into()),
             };
 
+            let auth = auth.clone();
+            let tor_addr = tor_addr.clone();
             tokio::spawn(async move {
-                let mut client = SOCKClient::new_no_auth(stream.compat(), None);
-                match client.init().await {
+                let mut server = match auth {
+                    Some((user, pass)) => SocksServer::with_auth(stream.compat(), user, pass),
+                    None => SocksServer::new(stream.compat()),
+                };
+                if tor {
+                    server = server.with_tor(tor_addr);
+                }
+                match server.handle().await {
                     Ok(_) => info!("client connected"),
                     Err(e) => error!("client error: {:?}", e),
                 }
             });
         }
     }
-} 
\ No newline at end of file
+}
+
+// Build the rustls client configuration: trust the supplied CA bundle (or the
+// platform roots) and, when a pin is configured, require the leaf certificate's
+// SubjectPublicKeyInfo to match the pinned hash.
+fn build_tls_config(ca_bundle: &Option<String>, pin: &Option<String>) -> Result<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    if let Some(path) = ca_bundle {
+        let mut reader = BufReader::new(File::open(path)?);
+        for cert in rustls_pemfile::certs(&mut reader) {
+            roots.add(cert?)?;
+        }
+    } else {
+        for cert in rustls_native_certs::load_native_certs()?.into_iter() {
+            roots.add(cert)?;
+        }
+    }
+
+    // Pin the crypto provider explicitly so construction never depends on a
+    // process-level default having been installed.
+    let provider = tokio_rustls::rustls::crypto::ring::default_provider();
+    let config = match pin {
+        Some(pin) => {
+            let verifier = PinnedCertVerifier::new(pin, roots, &provider)?;
+            ClientConfig::builder_with_provider(Arc::new(provider))
+                .with_safe_default_protocol_versions()?
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(verifier))
+                .with_no_client_auth()
+        }
+        None => ClientConfig::builder_with_provider(Arc::new(provider))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth(),
+    };
+
+    Ok(config)
+}
+
+// Discover the local IP the kernel would use to reach `dst`, without sending
+// any traffic, by connecting an unbound UDP socket and reading its bound
+// address. Only the IP is meaningful here; the UDP socket's ephemeral port is
+// unrelated to the eventual TCP source port and is discarded by the caller.
+fn local_ip_for(dst: SocketAddr) -> Result<IpAddr> {
+    let bind = if dst.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    let socket = UdpSocket::bind(bind)?;
+    socket.connect(dst)?;
+    Ok(socket.local_addr()?.ip())
+}
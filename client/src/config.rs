@@ -14,4 +14,25 @@ pub struct Config {
 
     #[arg(long, help = "Use Tor for connection", value_name = "tor", action = clap::ArgAction::SetTrue)]
     pub tor: bool,
+
+    #[arg(long, help = "Address of the Tor SOCKS5 listener", value_name = "tor-addr")]
+    pub tor_addr: Option<String>,
+
+    #[arg(long, help = "Path to a trusted CA bundle (PEM) for TLS", value_name = "ca-bundle")]
+    pub ca_bundle: Option<String>,
+
+    #[arg(long, help = "Pinned server SPKI hash (base64 SHA-256)", value_name = "pin")]
+    pub pin: Option<String>,
+
+    #[arg(long, help = "Require this username for SOCKS5 auth", value_name = "username")]
+    pub username: Option<String>,
+
+    #[arg(long, help = "Require this password for SOCKS5 auth", value_name = "password")]
+    pub password: Option<String>,
+
+    #[arg(long, help = "Send a PROXY protocol v2 header", value_name = "proxy-protocol", action = clap::ArgAction::SetTrue)]
+    pub proxy_protocol: bool,
+
+    #[arg(long, help = "Transport chain, outermost first (e.g. \"tls,tcp\")", value_name = "transport")]
+    pub transport: Option<String>,
 } 
\ No newline at end of file
@@ -0,0 +1,3 @@
+// Boxed error type shared across the client. `Send + Sync` so it survives the
+// async transport boundary and can be returned from spawned tasks.
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
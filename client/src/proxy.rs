@@ -0,0 +1,85 @@
+use std::net::SocketAddr;
+
+// PROXY protocol v2 signature (see the HAProxy spec).
+const SIGNATURE: [u8; 12] = [
+    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+// Version 2, command PROXY.
+const VER_CMD: u8 = 0x21;
+const TCP_IPV4: u8 = 0x11;
+const TCP_IPV6: u8 = 0x21;
+
+// Encode a PROXY protocol v2 header describing the `src -> dst` TCP connection so
+// the listening server can recover the agent's real peer address behind the
+// yamux multiplexing.
+pub fn header_v2(src: SocketAddr, dst: SocketAddr) -> Vec<u8> {
+    let mut header = Vec::with_capacity(28);
+    header.extend_from_slice(&SIGNATURE);
+    header.push(VER_CMD);
+
+    let mut addrs = Vec::new();
+    match (src, dst) {
+        (SocketAddr::V4(src), SocketAddr::V4(dst)) => {
+            header.push(TCP_IPV4);
+            addrs.extend_from_slice(&src.ip().octets());
+            addrs.extend_from_slice(&dst.ip().octets());
+            addrs.extend_from_slice(&src.port().to_be_bytes());
+            addrs.extend_from_slice(&dst.port().to_be_bytes());
+        }
+        (src, dst) => {
+            header.push(TCP_IPV6);
+            addrs.extend_from_slice(&ipv6_octets(src));
+            addrs.extend_from_slice(&ipv6_octets(dst));
+            addrs.extend_from_slice(&src.port().to_be_bytes());
+            addrs.extend_from_slice(&dst.port().to_be_bytes());
+        }
+    }
+
+    header.extend_from_slice(&(addrs.len() as u16).to_be_bytes());
+    header.extend_from_slice(&addrs);
+    header
+}
+
+// Render any address as 16 IPv6 octets, mapping IPv4 into the IPv4-mapped range.
+fn ipv6_octets(addr: SocketAddr) -> [u8; 16] {
+    match addr {
+        SocketAddr::V6(v6) => v6.ip().octets(),
+        SocketAddr::V4(v4) => v4.ip().to_ipv6_mapped().octets(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_v2_ipv4_layout() {
+        let src = "10.0.0.1:4000".parse().unwrap();
+        let dst = "93.184.216.34:443".parse().unwrap();
+        let header = header_v2(src, dst);
+
+        assert_eq!(&header[..12], &SIGNATURE);
+        assert_eq!(header[12], VER_CMD);
+        assert_eq!(header[13], TCP_IPV4);
+        // 4 + 4 address bytes + 2 + 2 port bytes.
+        assert_eq!(&header[14..16], &12u16.to_be_bytes());
+
+        let addrs = &header[16..];
+        assert_eq!(&addrs[0..4], &[10, 0, 0, 1]);
+        assert_eq!(&addrs[4..8], &[93, 184, 216, 34]);
+        assert_eq!(&addrs[8..10], &4000u16.to_be_bytes());
+        assert_eq!(&addrs[10..12], &443u16.to_be_bytes());
+    }
+
+    #[test]
+    fn header_v2_ipv6_family() {
+        let src = "[::1]:4000".parse().unwrap();
+        let dst = "[2606:2800:220:1:248:1893:25c8:1946]:443".parse().unwrap();
+        let header = header_v2(src, dst);
+
+        assert_eq!(header[13], TCP_IPV6);
+        // 16 + 16 address bytes + 2 + 2 port bytes.
+        assert_eq!(&header[14..16], &36u16.to_be_bytes());
+        assert_eq!(header.len(), 16 + 36);
+    }
+}
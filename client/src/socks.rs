@@ -1,64 +1,106 @@
 use std::io;
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::Arc;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
-use tokio::net::{TcpStream, lookup_host};
+use tokio::net::{TcpStream, UdpSocket, lookup_host};
 
 const SOCKS_VERSION: u8 = 0x05;
 const NO_AUTH: u8 = 0x00;
+const USER_PASS: u8 = 0x02;
+const NO_ACCEPTABLE: u8 = 0xFF;
+const USER_PASS_VERSION: u8 = 0x01;
 const CONNECT: u8 = 0x01;
+const UDP_ASSOCIATE: u8 = 0x03;
+const RESOLVE: u8 = 0xF0;
 const IPV4: u8 = 0x01;
 const DOMAIN: u8 = 0x03;
 
+// Largest datagram we buffer when relaying UDP over the stream.
+const UDP_BUF_SIZE: usize = 64 * 1024;
+
+// Default address of a local Tor daemon's SOCKS5 listener.
+const DEFAULT_TOR_ADDR: &str = "127.0.0.1:9050";
+
+// A request target parsed from the CONNECT header, kept in its original form so
+// that a domain can be forwarded to Tor for resolution instead of being looked
+// up locally.
+enum Target {
+    Ip(Ipv4Addr),
+    Domain(String),
+}
+
 pub struct SocksServer<T: AsyncRead + AsyncWrite + Unpin> {
     stream: T,
+    auth: Option<(String, String)>,
+    tor: Option<String>,
 }
 
 impl<T: AsyncRead + AsyncWrite + Unpin> SocksServer<T> {
     pub fn new(stream: T) -> Self {
-        Self { stream }
+        Self { stream, auth: None, tor: None }
+    }
+
+    pub fn with_auth(stream: T, username: String, password: String) -> Self {
+        Self { stream, auth: Some((username, password)), tor: None }
+    }
+
+    // Route outbound connections through the given Tor SOCKS5 listener, or the
+    // default `127.0.0.1:9050` when `addr` is `None`.
+    pub fn with_tor(mut self, addr: Option<String>) -> Self {
+        self.tor = Some(addr.unwrap_or_else(|| DEFAULT_TOR_ADDR.to_string()));
+        self
     }
 
     pub async fn handle(&mut self) -> io::Result<()> {
         // Handle auth
         let mut header = [0u8; 2];
         self.stream.read_exact(&mut header).await?;
-        
+
         if header[0] != SOCKS_VERSION {
             return Ok(());
         }
-        
+
         let mut methods = vec![0u8; header[1] as usize];
         self.stream.read_exact(&mut methods).await?;
-        
-        // Send auth response (no auth)
-        self.stream.write_all(&[SOCKS_VERSION, NO_AUTH]).await?;
+
+        // Select an authentication method: username/password when credentials
+        // are configured, no-auth otherwise. The client must have advertised
+        // the method we require; if it didn't, reply with `NO_ACCEPTABLE` and
+        // close.
+        let required = if self.auth.is_some() { USER_PASS } else { NO_AUTH };
+        if !methods.contains(&required) {
+            self.stream.write_all(&[SOCKS_VERSION, NO_ACCEPTABLE]).await?;
+            return Ok(());
+        }
+        self.stream.write_all(&[SOCKS_VERSION, required]).await?;
+
+        if self.auth.is_some() && !self.negotiate_user_pass().await? {
+            return Ok(());
+        }
 
         // Handle request
         let mut req_header = [0u8; 4];
         self.stream.read_exact(&mut req_header).await?;
 
-        if req_header[1] != CONNECT {
+        let command = req_header[1];
+        if command != CONNECT && command != RESOLVE && command != UDP_ASSOCIATE {
             return Ok(());
         }
 
-        // Parse address
-        let addr = match req_header[3] {
+        // Parse address, preserving a domain name verbatim so it can be handed
+        // to Tor for resolution inside the circuit.
+        let target = match req_header[3] {
             IPV4 => {
                 let mut addr = [0u8; 4];
                 self.stream.read_exact(&mut addr).await?;
-                let addr = SocketAddr::V4(SocketAddrV4::new(
-                    Ipv4Addr::new(addr[0], addr[1], addr[2], addr[3]),
-                    0,
-                ));
-                vec![addr]
+                Target::Ip(Ipv4Addr::new(addr[0], addr[1], addr[2], addr[3]))
             }
             DOMAIN => {
                 let mut len = [0u8; 1];
                 self.stream.read_exact(&mut len).await?;
                 let mut domain = vec![0u8; len[0] as usize];
                 self.stream.read_exact(&mut domain).await?;
-                let domain = String::from_utf8_lossy(&domain);
-                lookup_host(format!("{}:0", domain)).await?.collect()
+                Target::Domain(String::from_utf8_lossy(&domain).into_owned())
             }
             _ => return Ok(()),
         };
@@ -68,8 +110,26 @@ impl<T: AsyncRead + AsyncWrite + Unpin> SocksServer<T> {
         self.stream.read_exact(&mut port).await?;
         let port = ((port[0] as u16) << 8) | port[1] as u16;
 
-        // Connect to target
-        let mut target = TcpStream::connect(format!("{}:{}", addr[0].ip(), port)).await?;
+        // RESOLVE is Tor's domain-lookup extension; it has no byte stream to
+        // proxy, so resolve and reply before returning.
+        if command == RESOLVE {
+            return self.handle_resolve(target).await;
+        }
+
+        // UDP ASSOCIATE relays datagrams over the yamux stream rather than a
+        // TCP connection.
+        if command == UDP_ASSOCIATE {
+            return self.handle_udp_associate().await;
+        }
+
+        // Connect to target, optionally through the Tor SOCKS5 listener.
+        let mut target = match &self.tor {
+            Some(tor_addr) => tor_connect(tor_addr, &target, port).await?,
+            None => {
+                let addr = resolve(&target, port).await?;
+                TcpStream::connect(addr).await?
+            }
+        };
 
         // Send success response
         let response = [SOCKS_VERSION, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
@@ -80,4 +140,355 @@ impl<T: AsyncRead + AsyncWrite + Unpin> SocksServer<T> {
 
         Ok(())
     }
+
+    // Answer a RESOLVE request, delegating the lookup to Tor when enabled so the
+    // query travels through the circuit rather than leaking to the local
+    // resolver.
+    async fn handle_resolve(&mut self, target: Target) -> io::Result<()> {
+        let resolved = match &self.tor {
+            Some(tor_addr) => tor_resolve(tor_addr, &target).await?,
+            None => match resolve(&target, 0).await? {
+                SocketAddr::V4(v4) => *v4.ip(),
+                // The reply only models an IPv4 address; signal a general
+                // failure rather than leaving the client waiting on a reply.
+                SocketAddr::V6(_) => {
+                    let response = [SOCKS_VERSION, 0x01, 0x00, IPV4, 0, 0, 0, 0, 0, 0];
+                    self.stream.write_all(&response).await?;
+                    return Ok(());
+                }
+            },
+        };
+
+        let octets = resolved.octets();
+        let response = [
+            SOCKS_VERSION, 0x00, 0x00, IPV4,
+            octets[0], octets[1], octets[2], octets[3], 0, 0,
+        ];
+        self.stream.write_all(&response).await?;
+
+        Ok(())
+    }
+
+    // Bind a local UDP socket and relay datagrams between it and the client over
+    // the reliable yamux stream. Because the stream is a byte stream rather than
+    // a packet transport, each datagram is framed with a 2-byte big-endian
+    // length prefix (mirroring the wireguard-over-TCP framing) wrapping the
+    // standard SOCKS5 UDP request header.
+    async fn handle_udp_associate(&mut self) -> io::Result<()> {
+        let socket = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+        let bound = socket.local_addr()?;
+
+        // Report the bound address so the client knows where to send payloads.
+        let (ip, port) = match bound {
+            SocketAddr::V4(v4) => (v4.ip().octets(), v4.port()),
+            // The reply only models an IPv4 bind address; signal a general
+            // failure rather than leaving the client waiting on a reply.
+            SocketAddr::V6(_) => {
+                let response = [SOCKS_VERSION, 0x01, 0x00, IPV4, 0, 0, 0, 0, 0, 0];
+                self.stream.write_all(&response).await?;
+                return Ok(());
+            }
+        };
+        let response = [
+            SOCKS_VERSION, 0x00, 0x00, IPV4,
+            ip[0], ip[1], ip[2], ip[3], (port >> 8) as u8, (port & 0xff) as u8,
+        ];
+        self.stream.write_all(&response).await?;
+
+        let (mut reader, mut writer) = tokio::io::split(&mut self.stream);
+
+        // Stream -> UDP: decode each framed request header and forward its
+        // payload to the requested destination.
+        let outbound = {
+            let socket = Arc::clone(&socket);
+            async move {
+                let mut len = [0u8; 2];
+                loop {
+                    reader.read_exact(&mut len).await?;
+                    let frame_len = ((len[0] as usize) << 8) | len[1] as usize;
+                    let mut frame = vec![0u8; frame_len];
+                    reader.read_exact(&mut frame).await?;
+                    if let Some((dest, data)) = decode_udp_frame(&frame).await? {
+                        socket.send_to(data, dest).await?;
+                    }
+                }
+            }
+        };
+
+        // UDP -> stream: re-encapsulate inbound datagrams with the SOCKS5 UDP
+        // header and the length prefix.
+        let inbound = {
+            let socket = Arc::clone(&socket);
+            async move {
+                let mut buf = vec![0u8; UDP_BUF_SIZE];
+                loop {
+                    let (n, src) = socket.recv_from(&mut buf).await?;
+                    let frame = encode_udp_frame(src, &buf[..n]);
+                    writer.write_all(&frame).await?;
+                }
+            }
+        };
+
+        // Either direction ending tears the association down.
+        tokio::select! {
+            r = outbound => r,
+            r = inbound => r,
+        }
+    }
+
+    // RFC 1929 username/password sub-negotiation. Returns whether the client
+    // authenticated successfully; on failure the caller closes the connection.
+    async fn negotiate_user_pass(&mut self) -> io::Result<bool> {
+        let mut version = [0u8; 1];
+        self.stream.read_exact(&mut version).await?;
+        if version[0] != USER_PASS_VERSION {
+            // Still owe the client the `[0x01, status]` reply before closing.
+            self.stream.write_all(&[USER_PASS_VERSION, 0x01]).await?;
+            return Ok(false);
+        }
+
+        let mut ulen = [0u8; 1];
+        self.stream.read_exact(&mut ulen).await?;
+        let mut username = vec![0u8; ulen[0] as usize];
+        self.stream.read_exact(&mut username).await?;
+
+        let mut plen = [0u8; 1];
+        self.stream.read_exact(&mut plen).await?;
+        let mut password = vec![0u8; plen[0] as usize];
+        self.stream.read_exact(&mut password).await?;
+
+        let ok = match &self.auth {
+            Some((user, pass)) => username == user.as_bytes() && password == pass.as_bytes(),
+            None => true,
+        };
+
+        let status = if ok { 0x00 } else { 0x01 };
+        self.stream.write_all(&[USER_PASS_VERSION, status]).await?;
+
+        Ok(ok)
+    }
+}
+
+// Decode a framed SOCKS5 UDP request header, returning the destination and a
+// slice of the payload. Fragmented datagrams (`FRAG != 0`) are unsupported and
+// dropped by returning `None`.
+async fn decode_udp_frame(frame: &[u8]) -> io::Result<Option<(SocketAddr, &[u8])>> {
+    if frame.len() < 4 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "short udp frame"));
+    }
+    if frame[2] != 0 {
+        return Ok(None);
+    }
+
+    let (dest, data) = match frame[3] {
+        IPV4 => {
+            if frame.len() < 10 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "short ipv4 udp frame"));
+            }
+            let ip = Ipv4Addr::new(frame[4], frame[5], frame[6], frame[7]);
+            let port = ((frame[8] as u16) << 8) | frame[9] as u16;
+            (SocketAddr::V4(SocketAddrV4::new(ip, port)), &frame[10..])
+        }
+        DOMAIN => {
+            let dlen = frame[4] as usize;
+            let end = 5 + dlen;
+            if frame.len() < end + 2 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "short domain udp frame"));
+            }
+            let domain = String::from_utf8_lossy(&frame[5..end]);
+            let port = ((frame[end] as u16) << 8) | frame[end + 1] as u16;
+            let dest = lookup_host(format!("{}:{}", domain, port))
+                .await?
+                .next()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses for domain"))?;
+            (dest, &frame[end + 2..])
+        }
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported udp atyp")),
+    };
+
+    Ok(Some((dest, data)))
+}
+
+// Encapsulate an inbound datagram from `src` in a length-prefixed SOCKS5 UDP
+// header followed by its payload.
+fn encode_udp_frame(src: SocketAddr, data: &[u8]) -> Vec<u8> {
+    let mut header = vec![0x00, 0x00, 0x00];
+    match src {
+        SocketAddr::V4(v4) => {
+            header.push(IPV4);
+            header.extend_from_slice(&v4.ip().octets());
+            header.extend_from_slice(&v4.port().to_be_bytes());
+        }
+        SocketAddr::V6(v6) => {
+            header.push(0x04);
+            header.extend_from_slice(&v6.ip().octets());
+            header.extend_from_slice(&v6.port().to_be_bytes());
+        }
+    }
+    header.extend_from_slice(data);
+
+    let len = header.len() as u16;
+    let mut frame = Vec::with_capacity(2 + header.len());
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(&header);
+    frame
+}
+
+// Resolve a target to a concrete socket address using the local resolver.
+async fn resolve(target: &Target, port: u16) -> io::Result<SocketAddr> {
+    match target {
+        Target::Ip(ip) => Ok(SocketAddr::V4(SocketAddrV4::new(*ip, port))),
+        Target::Domain(domain) => lookup_host(format!("{}:{}", domain, port))
+            .await?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses for domain")),
+    }
+}
+
+// Open a TCP connection to `target:port` through a Tor SOCKS5 listener, handing
+// the domain form to Tor so DNS resolution happens inside the circuit.
+async fn tor_connect(tor_addr: &str, target: &Target, port: u16) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(tor_addr).await?;
+    tor_request(&mut stream, CONNECT, target, port).await?;
+    Ok(stream)
+}
+
+// Resolve a domain through Tor's RESOLVE extension, returning the IPv4 address
+// reported in the reply's bound-address field.
+async fn tor_resolve(tor_addr: &str, target: &Target) -> io::Result<Ipv4Addr> {
+    let mut stream = TcpStream::connect(tor_addr).await?;
+    let bound = tor_request(&mut stream, RESOLVE, target, 0).await?;
+    Ok(Ipv4Addr::new(bound[0], bound[1], bound[2], bound[3]))
+}
+
+// Perform the SOCKS5 method negotiation and issue `command` against the Tor
+// listener, returning the IPv4 bound address from the reply.
+async fn tor_request<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    command: u8,
+    target: &Target,
+    port: u16,
+) -> io::Result<[u8; 4]> {
+    // Method negotiation: offer only no-auth.
+    stream.write_all(&[SOCKS_VERSION, 0x01, NO_AUTH]).await?;
+    let mut method = [0u8; 2];
+    stream.read_exact(&mut method).await?;
+    if method[1] != NO_AUTH {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "tor rejected no-auth"));
+    }
+
+    // Request: prefer the DOMAIN address form so Tor resolves the name.
+    let mut req = vec![SOCKS_VERSION, command, 0x00];
+    match target {
+        Target::Domain(domain) => {
+            req.push(DOMAIN);
+            req.push(domain.len() as u8);
+            req.extend_from_slice(domain.as_bytes());
+        }
+        Target::Ip(ip) => {
+            req.push(IPV4);
+            req.extend_from_slice(&ip.octets());
+        }
+    }
+    req.push((port >> 8) as u8);
+    req.push((port & 0xff) as u8);
+    stream.write_all(&req).await?;
+
+    // Reply: VER REP RSV ATYP BND.ADDR BND.PORT.
+    let mut head = [0u8; 4];
+    stream.read_exact(&mut head).await?;
+    if head[1] != 0x00 {
+        return Err(io::Error::new(io::ErrorKind::ConnectionRefused, "tor request failed"));
+    }
+
+    let mut bound = [0u8; 4];
+    match head[3] {
+        IPV4 => stream.read_exact(&mut bound).await?,
+        DOMAIN => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len).await?;
+            let mut skip = vec![0u8; len[0] as usize];
+            stream.read_exact(&mut skip).await?;
+        }
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected bind atyp")),
+    }
+
+    let mut bound_port = [0u8; 2];
+    stream.read_exact(&mut bound_port).await?;
+
+    Ok(bound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // Drive `negotiate_user_pass` against an in-memory duplex and return the
+    // outcome alongside the two reply bytes the server wrote back.
+    async fn run_negotiation(creds: (&str, &str), client_bytes: &[u8]) -> (bool, [u8; 2]) {
+        let (mut client, server) = tokio::io::duplex(64);
+        client.write_all(client_bytes).await.unwrap();
+
+        let mut socks =
+            SocksServer::with_auth(server, creds.0.to_string(), creds.1.to_string());
+        let ok = socks.negotiate_user_pass().await.unwrap();
+
+        let mut reply = [0u8; 2];
+        client.read_exact(&mut reply).await.unwrap();
+        (ok, reply)
+    }
+
+    #[tokio::test]
+    async fn user_pass_accepts_matching_credentials() {
+        let (ok, reply) = run_negotiation(("alice", "secret"), &[
+            USER_PASS_VERSION, 5, b'a', b'l', b'i', b'c', b'e', 6, b's', b'e', b'c', b'r', b'e',
+            b't',
+        ])
+        .await;
+        assert!(ok);
+        assert_eq!(reply, [USER_PASS_VERSION, 0x00]);
+    }
+
+    #[tokio::test]
+    async fn user_pass_rejects_wrong_password() {
+        let (ok, reply) = run_negotiation(("alice", "secret"), &[
+            USER_PASS_VERSION, 5, b'a', b'l', b'i', b'c', b'e', 3, b'b', b'a', b'd',
+        ])
+        .await;
+        assert!(!ok);
+        assert_eq!(reply, [USER_PASS_VERSION, 0x01]);
+    }
+
+    #[tokio::test]
+    async fn user_pass_replies_on_bad_version() {
+        let (ok, reply) = run_negotiation(("alice", "secret"), &[0x02, 1, b'a', 1, b'b']).await;
+        assert!(!ok);
+        assert_eq!(reply, [USER_PASS_VERSION, 0x01]);
+    }
+
+    #[tokio::test]
+    async fn udp_frame_roundtrip_ipv4() {
+        let src: SocketAddr = "1.2.3.4:53".parse().unwrap();
+        let frame = encode_udp_frame(src, b"hello");
+        // The wire frame carries a 2-byte length prefix; the decoder is handed
+        // the body after that prefix is stripped.
+        let len = ((frame[0] as usize) << 8) | frame[1] as usize;
+        assert_eq!(len, frame.len() - 2);
+
+        let (dest, data) = decode_udp_frame(&frame[2..]).await.unwrap().unwrap();
+        assert_eq!(dest, src);
+        assert_eq!(data, b"hello");
+    }
+
+    #[tokio::test]
+    async fn udp_frame_drops_fragments() {
+        let frame = [0x00, 0x00, 0x01, IPV4, 1, 2, 3, 4, 0, 53, b'x'];
+        assert!(decode_udp_frame(&frame).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn udp_frame_rejects_short_input() {
+        assert!(decode_udp_frame(&[0x00, 0x00]).await.is_err());
+    }
 }
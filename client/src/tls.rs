@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::client::WebPkiServerVerifier;
+use tokio_rustls::rustls::crypto::{
+    verify_tls12_signature, verify_tls13_signature, CryptoProvider, WebPkiSupportedAlgorithms,
+};
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{DigitallySignedStruct, Error, RootCertStore, SignatureScheme};
+
+use crate::error::Result;
+
+// A certificate verifier that accepts the presented leaf certificate when its
+// SubjectPublicKeyInfo hashes to a pinned value, and otherwise defers to normal
+// webpki verification against the configured roots.
+#[derive(Debug)]
+pub struct PinnedCertVerifier {
+    pinned: Vec<u8>,
+    inner: Arc<WebPkiServerVerifier>,
+    // The signature-verification algorithms are copied from the crypto provider
+    // up front; `WebPkiServerVerifier` offers no accessor to reach them later.
+    supported_algs: WebPkiSupportedAlgorithms,
+}
+
+impl PinnedCertVerifier {
+    // `pin` is the base64-encoded SHA-256 of the server's SPKI; `provider`
+    // supplies both the webpki verification algorithms and the roots builder.
+    pub fn new(pin: &str, roots: RootCertStore, provider: &CryptoProvider) -> Result<Self> {
+        let pinned = base64::engine::general_purpose::STANDARD
+            .decode(pin)
+            .map_err(|e| format!("invalid pin: {}", e))?;
+        let inner =
+            WebPkiServerVerifier::builder_with_provider(Arc::new(roots), Arc::new(provider.clone()))
+                .build()?;
+        Ok(Self {
+            pinned,
+            inner,
+            supported_algs: provider.signature_verification_algorithms,
+        })
+    }
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, Error> {
+        if spki_hash(end_entity)? == self.pinned {
+            return Ok(ServerCertVerified::assertion());
+        }
+        self.inner
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, Error> {
+        verify_tls12_signature(message, cert, dss, &self.supported_algs)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, Error> {
+        verify_tls13_signature(message, cert, dss, &self.supported_algs)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+// Extract and hash the SubjectPublicKeyInfo of a DER-encoded certificate.
+fn spki_hash(cert: &CertificateDer<'_>) -> std::result::Result<Vec<u8>, Error> {
+    use x509_parser::prelude::FromDer;
+    let (_, parsed) = x509_parser::certificate::X509Certificate::from_der(cert.as_ref())
+        .map_err(|_| Error::General("failed to parse certificate".into()))?;
+    let spki = parsed.tbs_certificate.subject_pki.raw;
+    Ok(Sha256::digest(spki).to_vec())
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls::ClientConfig;
+use tokio_rustls::rustls::pki_types::ServerName;
+
+use crate::error::Result;
+
+// Marker for any byte stream the yamux `Connection` can be built on top of.
+pub trait AsyncStream: AsyncRead + AsyncWrite {}
+impl<T: AsyncRead + AsyncWrite> AsyncStream for T {}
+
+pub type BoxedStream = Box<dyn AsyncStream + Send + Unpin + 'static>;
+
+// A pluggable way to reach the agent server. Implementations establish (and
+// optionally wrap) the underlying connection; wrapping transports hold an inner
+// `Transport` so obfuscation layers can be chained on top of TCP or TLS.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    async fn connect(&self, host: &str, port: u16) -> Result<BoxedStream>;
+}
+
+// Plain TCP, the innermost transport in every chain.
+pub struct TcpTransport;
+
+#[async_trait]
+impl Transport for TcpTransport {
+    async fn connect(&self, host: &str, port: u16) -> Result<BoxedStream> {
+        let stream = TcpStream::connect(format!("{}:{}", host, port)).await?;
+        Ok(Box::new(stream))
+    }
+}
+
+// TLS layered over an inner transport (plain TCP by default).
+pub struct TlsTransport {
+    inner: Box<dyn Transport>,
+    config: Arc<ClientConfig>,
+}
+
+impl TlsTransport {
+    pub fn new(inner: Box<dyn Transport>, config: Arc<ClientConfig>) -> Self {
+        Self { inner, config }
+    }
+}
+
+#[async_trait]
+impl Transport for TlsTransport {
+    async fn connect(&self, host: &str, port: u16) -> Result<BoxedStream> {
+        let stream = self.inner.connect(host, port).await?;
+        let connector = TlsConnector::from(self.config.clone());
+        let domain = ServerName::try_from(host.to_string())
+            .map_err(|_| format!("invalid server name: {}", host))?;
+        Ok(Box::new(connector.connect(domain, stream).await?))
+    }
+}
+
+// A factory that wraps an inner transport in a named layer.
+type LayerFactory = Box<dyn Fn(Box<dyn Transport>) -> Result<Box<dyn Transport>> + Send + Sync>;
+
+// Registry of named wrapping transports keyed by name, so callers can register
+// their own obfuscation layers and select among them by `Config`. Plain TCP is
+// always the implicit innermost base; registered layers wrap whatever sits
+// beneath them, allowing chains such as `obfs,tls`.
+#[derive(Default)]
+pub struct TransportRegistry {
+    layers: HashMap<String, LayerFactory>,
+}
+
+impl TransportRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Register a wrapping transport under `name`. The factory receives the
+    // transport it should wrap and returns the new outer transport.
+    pub fn register<F>(&mut self, name: &str, factory: F)
+    where
+        F: Fn(Box<dyn Transport>) -> Result<Box<dyn Transport>> + Send + Sync + 'static,
+    {
+        self.layers.insert(name.to_string(), Box::new(factory));
+    }
+
+    // Build a transport from a comma-separated spec written outermost-first
+    // (e.g. `obfs,tls`). Every name must resolve to a registered layer; the
+    // literal `tcp` names the implicit base and is only valid as the innermost
+    // element, so a chain like `tcp,tls` is rejected rather than silently
+    // dropping the wrapped layers.
+    pub fn build(&self, spec: &str) -> Result<Box<dyn Transport>> {
+        let layers: Vec<&str> =
+            spec.split(',').map(str::trim).filter(|n| !n.is_empty()).collect();
+
+        let mut transport: Box<dyn Transport> = Box::new(TcpTransport);
+        // Apply layers innermost-first, i.e. the reverse of the spec order.
+        for (depth, name) in layers.iter().rev().enumerate() {
+            if *name == "tcp" {
+                if depth != 0 {
+                    return Err(
+                        "`tcp` is the base transport and cannot wrap another layer".into(),
+                    );
+                }
+                continue;
+            }
+            let factory = self
+                .layers
+                .get(*name)
+                .ok_or_else(|| format!("unknown transport: {}", name))?;
+            transport = factory(transport)?;
+        }
+
+        Ok(transport)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A registry whose `tls` layer is a no-op wrapper, so `build` can be
+    // exercised without opening sockets.
+    fn registry() -> TransportRegistry {
+        let mut registry = TransportRegistry::new();
+        registry.register("tls", Ok);
+        registry
+    }
+
+    #[test]
+    fn plain_base_specs_build() {
+        assert!(registry().build("").is_ok());
+        assert!(registry().build("tcp").is_ok());
+    }
+
+    #[test]
+    fn registered_layer_builds() {
+        assert!(registry().build("tls").is_ok());
+    }
+
+    #[test]
+    fn tcp_wrapping_a_layer_is_rejected() {
+        let err = registry().build("tcp,tls").unwrap_err().to_string();
+        assert!(err.contains("tcp"));
+    }
+
+    #[test]
+    fn unknown_layer_is_rejected() {
+        let err = registry().build("bogus").unwrap_err().to_string();
+        assert!(err.contains("unknown transport"));
+    }
+}